@@ -0,0 +1,175 @@
+use anyhow::{bail, Result};
+use image::DynamicImage;
+use std::f64::consts::PI;
+
+const BASE83_CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// Encodes `image` as a BlurHash string with `components_x * components_y` basis pairs.
+/// `components_x`/`components_y` must each be in `1..=9`.
+pub(crate) fn encode(image: &DynamicImage, components_x: u32, components_y: u32) -> Result<String> {
+    if !(1..=9).contains(&components_x) || !(1..=9).contains(&components_y) {
+        bail!("blurhash components must be in 1..=9, got ({components_x}, {components_y})");
+    }
+
+    let rgb = image.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let (width, height) = (width as f64, height as f64);
+
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for j in 0..components_y {
+        for i in 0..components_x {
+            let normalization = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut sum = [0.0f64; 3];
+            for (x, y, pixel) in rgb.enumerate_pixels() {
+                let basis = (PI * i as f64 * x as f64 / width).cos() * (PI * j as f64 * y as f64 / height).cos();
+                for c in 0..3 {
+                    sum[c] += basis * srgb_to_linear(pixel[c]);
+                }
+            }
+            let scale = normalization / (width * height);
+            factors.push([sum[0] * scale, sum[1] * scale, sum[2] * scale]);
+        }
+    }
+
+    let dc = factors[0];
+    let ac = &factors[1..];
+
+    let (quantized_max, actual_max) = quantize_max_ac(ac);
+
+    let size_flag = (components_x - 1) + (components_y - 1) * 9;
+    let mut hash = base83_encode(size_flag, 1);
+    hash.push_str(&base83_encode(quantized_max, 1));
+    hash.push_str(&base83_encode(encode_dc(dc), 4));
+    for &color in ac {
+        hash.push_str(&base83_encode(encode_ac(color, actual_max), 2));
+    }
+    Ok(hash)
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let value = value as f64;
+    if value > 10.31 {
+        ((value / 255.0 + 0.055) / 1.055).powf(2.4)
+    } else {
+        value / 255.0 / 12.92
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u32 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round().clamp(0.0, 255.0) as u32
+}
+
+/// Quantizes the maximum AC term *magnitude* to a base83 digit, returning `(quantized_max,
+/// actual_max)`. Must fold over `abs(value)`, not `value` itself: AC cosine-basis sums are
+/// frequently negative, so a plain `f64::max` fold (seeded at `0.0`) would collapse to `0` for any
+/// image whose AC components happen to all be negative, under-saturating every AC term below.
+fn quantize_max_ac(ac: &[[f64; 3]]) -> (u32, f64) {
+    let max_ac = ac.iter().flatten().cloned().fold(0.0f64, |acc, value| acc.max(value.abs()));
+    let quantized_max = if max_ac > 0.0 {
+        ((max_ac * 166.0 - 0.5).floor() as i32).clamp(0, 82) as u32
+    } else {
+        0
+    };
+    let actual_max = if quantized_max > 0 {
+        (quantized_max as f64 + 1.0) / 166.0
+    } else {
+        1.0
+    };
+    (quantized_max, actual_max)
+}
+
+fn encode_dc(color: [f64; 3]) -> u32 {
+    (linear_to_srgb(color[0]) << 16) + (linear_to_srgb(color[1]) << 8) + linear_to_srgb(color[2])
+}
+
+fn encode_ac(color: [f64; 3], max_value: f64) -> u32 {
+    let quantize = |c: f64| -> u32 {
+        let normalized = (c / max_value).signum() * (c / max_value).abs().powf(0.5);
+        ((normalized * 9.0 + 9.5).floor() as i32).clamp(0, 18) as u32
+    };
+    quantize(color[0]) * 19 * 19 + quantize(color[1]) * 19 + quantize(color[2])
+}
+
+fn base83_encode(mut value: u32, length: usize) -> String {
+    let mut digits = vec![0u8; length];
+    for slot in digits.iter_mut().rev() {
+        *slot = BASE83_CHARS[(value % 83) as usize];
+        value /= 83;
+    }
+    String::from_utf8(digits).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base83_encode_pads_to_requested_length() {
+        assert_eq!(base83_encode(0, 1), "0");
+        assert_eq!(base83_encode(0, 4), "0000");
+        assert_eq!(base83_encode(82, 1), "~");
+    }
+
+    #[test]
+    fn base83_encode_carries_into_higher_digits() {
+        // 83 is '0' in the ones place with a carry into the next, i.e. "10"
+        assert_eq!(base83_encode(83, 2), "10");
+    }
+
+    #[test]
+    fn srgb_linear_round_trip_is_stable() {
+        for value in [0u8, 1, 16, 128, 200, 255] {
+            let round_tripped = linear_to_srgb(srgb_to_linear(value));
+            assert!((round_tripped as i32 - value as i32).abs() <= 1, "{value} round-tripped to {round_tripped}");
+        }
+    }
+
+    #[test]
+    fn encode_dc_packs_full_white_as_0xffffff() {
+        assert_eq!(encode_dc([1.0, 1.0, 1.0]), 0xFFFFFF);
+    }
+
+    #[test]
+    fn encode_dc_packs_black_as_zero() {
+        assert_eq!(encode_dc([0.0, 0.0, 0.0]), 0);
+    }
+
+    #[test]
+    fn encode_ac_centers_zero_on_the_middle_quantization_bucket() {
+        // a fully neutral AC term (no signal relative to max_value) should land on bucket 9, the
+        // midpoint of the 0..=18 range, for every channel
+        assert_eq!(encode_ac([0.0, 0.0, 0.0], 1.0), 9 * 19 * 19 + 9 * 19 + 9);
+    }
+
+    #[test]
+    fn quantize_max_ac_uses_magnitude_not_signed_value() {
+        // every AC component negative: a naive `f64::max` fold (seeded at 0.0) would wrongly
+        // collapse this to a quantized_max of 0, as if the image carried no AC signal at all
+        let ac = [[-0.5, -0.5, -0.5], [-0.2, -0.1, -0.3]];
+        let (quantized_max, _actual_max) = quantize_max_ac(&ac);
+        assert!(quantized_max > 0, "negative-only AC components must still register a nonzero magnitude");
+    }
+
+    #[test]
+    fn encode_end_to_end_on_an_asymmetric_image_is_stable() {
+        // a hard left/right split produces strong AC components that are negative for some basis
+        // phases; encode() must not under-saturate (or panic on) this the way a signed-value fold
+        // over max_ac would
+        let mut img = image::RgbImage::new(8, 8);
+        for (x, _y, pixel) in img.enumerate_pixels_mut() {
+            *pixel = if x < 4 { image::Rgb([0, 0, 0]) } else { image::Rgb([255, 255, 255]) };
+        }
+        let (components_x, components_y) = (4, 3);
+        let hash = encode(&DynamicImage::ImageRgb8(img), components_x, components_y).unwrap();
+
+        assert_eq!(hash.len(), 1 + 1 + 4 + (components_x * components_y - 1) as usize * 2);
+        let quantized_max_char = hash.as_bytes()[1];
+        assert_ne!(quantized_max_char, BASE83_CHARS[0], "a sharp edge must register nonzero AC magnitude, not quantize to 0");
+    }
+}
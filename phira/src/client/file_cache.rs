@@ -0,0 +1,425 @@
+use super::store::{FsStore, Store};
+use crate::dir;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher},
+    ChaCha20,
+};
+use futures_util::{Stream, StreamExt};
+use once_cell::sync::{Lazy, OnceCell};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Default on-disk budget for cached `PZFile` bytes: 512 MiB.
+const DEFAULT_CAPACITY: u64 = 512 * 1024 * 1024;
+
+/// Length in bytes of the per-file nonce header prepended to encrypted cache entries.
+const NONCE_LEN: usize = 12;
+
+/// Key under which the LRU index itself is persisted in the backing [`Store`], alongside the
+/// cached file entries.
+const INDEX_KEY: &str = "__index__";
+
+/// When set, cached bytes are encrypted at rest with ChaCha20 under this key. Left unset, the
+/// cache stores plaintext, so existing deployments that never call [`set_encryption_key`] are
+/// unaffected.
+static ENCRYPTION_KEY: OnceCell<chacha20::Key> = OnceCell::new();
+
+/// Enables at-rest encryption for the file cache. Has no effect if called more than once; the
+/// first key wins for the lifetime of the process.
+pub fn set_encryption_key(key: chacha20::Key) {
+    let _ = ENCRYPTION_KEY.set(key);
+}
+
+fn random_nonce() -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    nonce
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Entry {
+    size_bytes: u64,
+    last_access: u64,
+    /// Whether this entry was written while [`ENCRYPTION_KEY`] was configured. Checked against
+    /// the key's current state on [`FileCache::get`] so an entry written before a key was
+    /// introduced, or while one was set that has since been removed, is treated as a miss,
+    /// forcing a re-fetch. Only the presence of a key is recorded: an entry written under one key
+    /// and read under a different key is not detected.
+    encrypted: bool,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Index {
+    entries: HashMap<String, Entry>,
+}
+
+/// Size-bounded cache for `PZFile` bytes, evicting least-recently-used entries once the total
+/// size of cached objects exceeds `capacity`. Generic over [`Store`] so the same LRU/encryption
+/// logic works whether objects live on local disk or in a shared S3-compatible bucket.
+pub struct FileCache {
+    store: Arc<dyn Store>,
+    capacity: u64,
+    index: Mutex<Option<Index>>,
+}
+
+pub static FILE_CACHE: Lazy<Arc<FileCache>> = Lazy::new(|| Arc::new(FileCache::with_capacity(DEFAULT_CAPACITY)));
+
+impl FileCache {
+    /// A `FileCache` backed by the local filesystem, under `dir::cache()/files`.
+    pub fn with_capacity(capacity: u64) -> Self {
+        let dir = PathBuf::from(dir::cache().unwrap_or_else(|_| ".".to_owned())).join("files");
+        Self::with_store(Arc::new(FsStore::new(dir)), capacity)
+    }
+
+    pub fn with_store(store: Arc<dyn Store>, capacity: u64) -> Self {
+        Self {
+            store,
+            capacity,
+            index: Mutex::new(None),
+        }
+    }
+
+    async fn ensure_index_loaded(&self) {
+        if self.index.lock().unwrap().is_some() {
+            return;
+        }
+        let loaded = self
+            .store
+            .get(INDEX_KEY)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        *self.index.lock().unwrap() = Some(loaded);
+    }
+
+    async fn save_index(&self) {
+        let snapshot = self.index.lock().unwrap().clone();
+        if let Some(index) = snapshot {
+            if let Ok(bytes) = serde_json::to_vec(&index) {
+                let _ = self.store.put(INDEX_KEY, Bytes::from(bytes)).await;
+            }
+        }
+    }
+
+    /// The cache key for a URL: not cryptographic, just stable and filesystem/object-key safe.
+    fn key_for(url: &str) -> String {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        url.hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Returns the cached bytes for `url`, bumping its `last_access` on a hit. Transparently
+    /// strips the nonce header and decrypts when [`ENCRYPTION_KEY`] is configured.
+    ///
+    /// If the entry's recorded `encrypted` flag disagrees with whether a key is configured right
+    /// now — i.e. a key was added or removed since the entry was written — the entry is dropped
+    /// and treated as a miss rather than risking decrypting plaintext as ciphertext or returning
+    /// undecrypted ciphertext as if it were plaintext. A key replaced by a different key is not
+    /// detected: such an entry is decrypted under the current key.
+    pub async fn get(&self, url: &str) -> Option<Bytes> {
+        let key = Self::key_for(url);
+        self.ensure_index_loaded().await;
+        let recorded_encrypted = self.index.lock().unwrap().as_ref()?.entries.get(&key)?.encrypted;
+        let currently_encrypted = ENCRYPTION_KEY.get().is_some();
+        if recorded_encrypted != currently_encrypted {
+            self.invalidate(&key).await;
+            return None;
+        }
+
+        let raw = self.store.get(&key).await.ok().flatten()?;
+        self.touch(&key, raw.len() as u64, recorded_encrypted).await;
+        let plain = match ENCRYPTION_KEY.get() {
+            Some(enc_key) if raw.len() >= NONCE_LEN => {
+                let (nonce, ciphertext) = raw.split_at(NONCE_LEN);
+                let mut buf = ciphertext.to_vec();
+                ChaCha20::new(enc_key, nonce.into()).apply_keystream(&mut buf);
+                buf
+            }
+            _ => raw.to_vec(),
+        };
+        Some(Bytes::from(plain))
+    }
+
+    async fn touch(&self, key: &str, size_bytes: u64, encrypted: bool) {
+        self.ensure_index_loaded().await;
+        {
+            let mut guard = self.index.lock().unwrap();
+            guard.get_or_insert_with(Index::default).entries.insert(
+                key.to_owned(),
+                Entry {
+                    size_bytes,
+                    last_access: now(),
+                    encrypted,
+                },
+            );
+        }
+        self.save_index().await;
+    }
+
+    /// Drops a single entry from both the index and the backing store, e.g. when it's found to
+    /// have been written under a since-changed encryption configuration.
+    async fn invalidate(&self, key: &str) {
+        {
+            let mut guard = self.index.lock().unwrap();
+            if let Some(index) = guard.as_mut() {
+                index.entries.remove(key);
+            }
+        }
+        let _ = self.store.remove(key).await;
+        self.save_index().await;
+    }
+
+    /// Stores `data` for `url`, encrypting it with a fresh per-file nonce when
+    /// [`ENCRYPTION_KEY`] is configured, then evicts LRU entries if over budget.
+    pub async fn put(&self, url: &str, data: &[u8]) -> Result<()> {
+        let key = Self::key_for(url);
+        let encrypted = ENCRYPTION_KEY.get().is_some();
+        let payload = match ENCRYPTION_KEY.get() {
+            Some(enc_key) => {
+                let nonce = random_nonce();
+                let mut ciphertext = data.to_vec();
+                ChaCha20::new(enc_key, &nonce.into()).apply_keystream(&mut ciphertext);
+                let mut framed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+                framed.extend_from_slice(&nonce);
+                framed.extend_from_slice(&ciphertext);
+                framed
+            }
+            None => data.to_vec(),
+        };
+        let size_bytes = payload.len() as u64;
+        self.store.put(&key, Bytes::from(payload)).await?;
+        self.touch(&key, size_bytes, encrypted).await;
+        self.evict_if_needed().await;
+        Ok(())
+    }
+
+    pub async fn len_bytes(&self) -> u64 {
+        self.ensure_index_loaded().await;
+        self.index
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|index| index.entries.values().map(|entry| entry.size_bytes).sum())
+            .unwrap_or(0)
+    }
+
+    /// Removes every cached entry and clears the index.
+    pub async fn purge(&self) -> Result<()> {
+        self.ensure_index_loaded().await;
+        let keys: Vec<String> = {
+            let mut guard = self.index.lock().unwrap();
+            let index = guard.get_or_insert_with(Index::default);
+            let keys = index.entries.keys().cloned().collect();
+            index.entries.clear();
+            keys
+        };
+        for key in keys {
+            let _ = self.store.remove(&key).await;
+        }
+        self.save_index().await;
+        Ok(())
+    }
+
+    /// Opens a fresh writer for `url`, to be filled chunk-by-chunk via
+    /// [`FileCacheWriter::write_chunk`] and finalized with [`FileCacheWriter::commit`] once the
+    /// source stream is exhausted. Chunks land in a local scratch temp file (bounded memory,
+    /// independent of the backing `Store`) and are only handed to `Store::put` as one completed
+    /// object on commit. When [`ENCRYPTION_KEY`] is configured, the nonce header is written up
+    /// front and every chunk is encrypted in place, advancing the same cipher's running block
+    /// counter.
+    ///
+    /// Takes `self` behind an `Arc` (rather than `&'static self`) so any `FileCache` instance can
+    /// use the streaming tee path, not just a `Lazy`-backed singleton like `FILE_CACHE` — e.g. a
+    /// second instance pointed at an `S3Store` for a multi-instance deployment.
+    pub async fn writer(self: Arc<Self>, url: &str) -> Result<FileCacheWriter> {
+        let key = Self::key_for(url);
+        let tmp_path = std::env::temp_dir().join(format!("phira-file-cache-{key}.tmp"));
+        let mut file = tokio::fs::File::create(&tmp_path)
+            .await
+            .with_context(|| format!("creating temp cache file for {url}"))?;
+
+        let mut written = 0u64;
+        let cipher = if let Some(enc_key) = ENCRYPTION_KEY.get() {
+            use tokio::io::AsyncWriteExt;
+            let nonce = random_nonce();
+            file.write_all(&nonce).await?;
+            written += NONCE_LEN as u64;
+            Some(ChaCha20::new(enc_key, &nonce.into()))
+        } else {
+            None
+        };
+
+        Ok(FileCacheWriter {
+            cache: self,
+            url: url.to_owned(),
+            key,
+            tmp_path,
+            file,
+            cipher,
+            written,
+        })
+    }
+
+    async fn evict_if_needed(&self) {
+        self.ensure_index_loaded().await;
+        let victims = {
+            let mut guard = self.index.lock().unwrap();
+            let index = guard.get_or_insert_with(Index::default);
+            let mut total: u64 = index.entries.values().map(|entry| entry.size_bytes).sum();
+            if total <= self.capacity {
+                return;
+            }
+            let mut by_age: Vec<(String, Entry)> = index.entries.iter().map(|(key, entry)| (key.clone(), *entry)).collect();
+            by_age.sort_by_key(|(_, entry)| entry.last_access);
+            let mut victims = Vec::new();
+            for (key, entry) in by_age {
+                if total <= self.capacity {
+                    break;
+                }
+                index.entries.remove(&key);
+                total = total.saturating_sub(entry.size_bytes);
+                victims.push(key);
+            }
+            victims
+        };
+        for key in &victims {
+            let _ = self.store.remove(key).await;
+        }
+        self.save_index().await;
+    }
+}
+
+/// Tees a `PZFile::fetch_stream` write-through to disk: every chunk yielded to the caller is
+/// also appended to a local temp file, which [`commit`](Self::commit) hands to the backing
+/// `Store` as one finished object once the source stream is fully drained (or discarded via
+/// [`abort`](Self::abort) on error). Memory use stays bounded to one chunk at a time while the
+/// stream is in flight.
+pub struct FileCacheWriter {
+    cache: Arc<FileCache>,
+    url: String,
+    key: String,
+    tmp_path: PathBuf,
+    file: tokio::fs::File,
+    cipher: Option<ChaCha20>,
+    written: u64,
+}
+impl FileCacheWriter {
+    async fn write_chunk(&mut self, chunk: &[u8]) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+        match &mut self.cipher {
+            Some(cipher) => {
+                let mut ciphertext = chunk.to_vec();
+                cipher.apply_keystream(&mut ciphertext);
+                self.file.write_all(&ciphertext).await?;
+            }
+            None => self.file.write_all(chunk).await?,
+        }
+        self.written += chunk.len() as u64;
+        Ok(())
+    }
+
+    async fn commit(self) -> Result<()> {
+        drop(self.file);
+        let payload = tokio::fs::read(&self.tmp_path)
+            .await
+            .with_context(|| format!("reading back temp cache file for {}", self.url))?;
+        let _ = tokio::fs::remove_file(&self.tmp_path).await;
+        self.cache.store.put(&self.key, Bytes::from(payload)).await?;
+        self.cache.touch(&self.key, self.written, self.cipher.is_some()).await;
+        self.cache.evict_if_needed().await;
+        Ok(())
+    }
+
+    async fn abort(self) {
+        drop(self.file);
+        let _ = tokio::fs::remove_file(&self.tmp_path).await;
+    }
+}
+
+/// Wraps a `reqwest` byte stream so each chunk is written through `writer` as it passes to the
+/// caller, committing the finished object once the source stream is exhausted.
+pub fn tee_to_cache<S>(writer: FileCacheWriter, stream: S) -> impl Stream<Item = reqwest::Result<Bytes>>
+where
+    S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
+{
+    futures_util::stream::unfold(Some((writer, stream)), |state| async move {
+        let (mut writer, mut stream) = state?;
+        match stream.next().await {
+            Some(Ok(bytes)) => {
+                // best-effort: a disk write failure shouldn't stop the caller's stream
+                let _ = writer.write_chunk(&bytes).await;
+                Some((Ok(bytes), Some((writer, stream))))
+            }
+            Some(Err(err)) => {
+                writer.abort().await;
+                Some((Err(err), None))
+            }
+            None => {
+                let _ = writer.commit().await;
+                None
+            }
+        }
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static DIR_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    fn fresh_store() -> Arc<dyn Store> {
+        let n = DIR_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("phira-file-cache-test-{}-{n}", std::process::id()));
+        Arc::new(FsStore::new(dir))
+    }
+
+    #[tokio::test]
+    async fn lru_evicts_least_recently_used_first() {
+        let cache = FileCache::with_store(fresh_store(), 30);
+        cache.put("a", &[0u8; 10]).await.unwrap();
+        cache.put("b", &[0u8; 10]).await.unwrap();
+        // re-touch "a" so "b" becomes the older (and thus next-evicted) entry
+        assert!(cache.get("a").await.is_some());
+        // total would be 40 bytes against a 30 byte budget: "b" must be the one evicted
+        cache.put("c", &[0u8; 20]).await.unwrap();
+
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("c").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn detects_stale_encryption_state_and_forces_a_miss() {
+        let cache = FileCache::with_store(fresh_store(), DEFAULT_CAPACITY);
+        cache.put("plain", b"hello world").await.unwrap();
+
+        // configuring a key after the entry above was written must not be applied retroactively;
+        // only the first call in the whole test binary actually takes effect, so skip the
+        // assertions entirely if some earlier test already claimed the key.
+        let already_keyed = ENCRYPTION_KEY.get().is_some();
+        set_encryption_key([9u8; 32].into());
+        if !already_keyed {
+            assert!(cache.get("plain").await.is_none(), "stale plaintext entry must not be returned as ciphertext");
+
+            cache.put("cipher", b"hello again").await.unwrap();
+            assert_eq!(cache.get("cipher").await.unwrap(), Bytes::from_static(b"hello again"));
+        }
+    }
+}
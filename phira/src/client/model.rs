@@ -1,6 +1,14 @@
+mod blurhash;
+
 mod chart;
 pub use chart::*;
 
+mod file_cache;
+pub use file_cache::*;
+
+mod store;
+pub use store::*;
+
 mod record;
 pub use record::*;
 
@@ -14,7 +22,7 @@ use crate::{
 };
 use anyhow::Result;
 use bytes::Bytes;
-use futures_util::Stream;
+use futures_util::{future::BoxFuture, FutureExt, Stream, StreamExt};
 use http_cache_reqwest::{CACacheManager, Cache, CacheMode, HttpCache};
 use image::DynamicImage;
 use lru::LruCache;
@@ -25,7 +33,8 @@ use std::{
     any::Any,
     collections::HashMap,
     marker::PhantomData,
-    sync::{Arc, Mutex},
+    path::PathBuf,
+    sync::{Arc, Mutex, Weak},
 };
 
 pub(crate) type ObjectMap<T> = LruCache<i32, Arc<T>>;
@@ -40,7 +49,78 @@ pub(crate) fn obtain_map_cache<T: PZObject + 'static>() -> Arc<Mutex<Box<dyn Any
     )
 }
 
-pub trait PZObject: Clone + DeserializeOwned + Send + Sync {
+/// Default on-disk budget for persisted `Ptr<T>` objects: much smaller than `FileCache`'s, since
+/// these are small serialized JSON blobs rather than file bytes.
+const OBJECT_CACHE_CAPACITY: u64 = 32 * 1024 * 1024;
+
+/// Backing cache for serialized `Ptr<T>` objects, so they survive process restarts the same way
+/// `FileCache` persists `PZFile` bytes. Reuses `FileCache` itself rather than a bespoke store, so
+/// objects get the same LRU/capacity bound. The bound is on total size only: a persisted object
+/// has no age limit, and [`Ptr::load`] returns it without a network refresh until capacity
+/// eviction removes it.
+static OBJECT_CACHE: Lazy<FileCache> = Lazy::new(|| {
+    let dir = PathBuf::from(dir::cache().unwrap_or_else(|_| ".".to_owned())).join("objects");
+    FileCache::with_store(Arc::new(FsStore::new(dir)), OBJECT_CACHE_CAPACITY)
+});
+
+/// Flat key for a `(T::QUERY_PATH, id)` pair, hashed the same way [`FileCache::key_for`] hashes
+/// URLs so it stays filesystem/object-key safe no matter what characters `QUERY_PATH` contains.
+fn object_key<T: PZObject>(id: i32) -> String {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    T::QUERY_PATH.hash(&mut hasher);
+    id.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Outcome of an in-flight fetch. `V` must be cheaply `Clone` (an `Arc<T>` or a refcounted
+/// `Bytes` buffer) so every waiter on a [`futures_util::future::Shared`] future gets its own
+/// copy of either the value or the error.
+type FetchOutcome<V> = std::result::Result<V, Arc<anyhow::Error>>;
+type SharedFetch<V> = futures_util::future::Shared<BoxFuture<'static, FetchOutcome<V>>>;
+
+/// Single-flight registry: `(QUERY_PATH, id) -> Weak<SharedFetch<Arc<T>>>`, so concurrent callers
+/// of [`Ptr::load`] for the same object join the one outstanding request instead of each firing
+/// their own. Keyed on `T::QUERY_PATH` because the map is type-erased behind `Any`.
+static WRITING_STATUS: Lazy<Mutex<HashMap<(&'static str, i32), Box<dyn Any + Send + Sync>>>> = Lazy::new(Mutex::default);
+
+/// Atomically joins an outstanding fetch for `(T::QUERY_PATH, id)`, or starts one via `make` and
+/// registers it, all under a single lock acquisition — otherwise two callers can both observe "no
+/// entry" and each register their own `Shared` future, clobbering one another and defeating the
+/// coalescing this map exists for. `make` is injected (rather than calling `Client::fetch`
+/// directly) so the coalescing logic itself is testable with a fake fetcher, independent of the
+/// network.
+fn get_or_start_inflight<T: PZObject + 'static>(id: i32, make: impl FnOnce() -> BoxFuture<'static, FetchOutcome<Arc<T>>>) -> Arc<SharedFetch<Arc<T>>> {
+    let mut status = WRITING_STATUS.lock().unwrap();
+    if let Some(existing) = status
+        .get(&(T::QUERY_PATH, id))
+        .and_then(|entry| entry.downcast_ref::<Weak<SharedFetch<Arc<T>>>>())
+        .and_then(Weak::upgrade)
+    {
+        return existing;
+    }
+    // building the future here is fine: an `async` block doesn't run until polled
+    let shared: Arc<SharedFetch<Arc<T>>> = Arc::new(make().shared());
+    status.insert((T::QUERY_PATH, id), Box::new(Arc::downgrade(&shared)));
+    shared
+}
+
+/// Removes the in-flight entry for `(T::QUERY_PATH, id)`, but only if it still points at
+/// `handle` — a waiter from an older generation must not clobber a newer call's still-in-flight
+/// registration for the same key.
+fn clear_inflight<T: PZObject + 'static>(id: i32, handle: &Arc<SharedFetch<Arc<T>>>) {
+    let mut status = WRITING_STATUS.lock().unwrap();
+    let still_current = status
+        .get(&(T::QUERY_PATH, id))
+        .and_then(|entry| entry.downcast_ref::<Weak<SharedFetch<Arc<T>>>>())
+        .and_then(Weak::upgrade)
+        .map_or(false, |current| Arc::ptr_eq(&current, handle));
+    if still_current {
+        status.remove(&(T::QUERY_PATH, id));
+    }
+}
+
+pub trait PZObject: Clone + Serialize + DeserializeOwned + Send + Sync {
     const QUERY_PATH: &'static str;
 
     fn id(&self) -> i32;
@@ -142,7 +222,37 @@ impl<T: PZObject + 'static> Ptr<T> {
             drop(guard);
             drop(map);
         }
-        self.fetch().await
+
+        let id = self.id;
+
+        // fall back to a persisted copy (e.g. from a prior process) before hitting the network
+        if let Some(bytes) = OBJECT_CACHE.get(&object_key::<T>(id)).await {
+            if let Ok(value) = serde_json::from_slice::<T>(&bytes) {
+                let value = Arc::new(value);
+                let map = obtain_map_cache::<T>();
+                let mut guard = map.lock().unwrap();
+                let Some(actual_map) = guard.downcast_mut::<ObjectMap::<T>>() else { unreachable!() };
+                actual_map.put(id, Arc::clone(&value));
+                return Ok(value);
+            }
+        }
+
+        // join an already-outstanding fetch for this id instead of firing our own
+        let shared = get_or_start_inflight::<T>(id, || async move { Client::fetch(id).await.map_err(Arc::new) }.boxed());
+        let result = (*shared).clone().await;
+        // drop the memoized entry regardless of outcome so a failed fetch can be retried
+        clear_inflight::<T>(id, &shared);
+        let value = result.map_err(|err| anyhow::anyhow!(err))?;
+
+        if let Ok(bytes) = serde_json::to_vec(&*value) {
+            let _ = OBJECT_CACHE.put(&object_key::<T>(id), &bytes).await;
+        }
+
+        let map = obtain_map_cache::<T>();
+        let mut guard = map.lock().unwrap();
+        let Some(actual_map) = guard.downcast_mut::<ObjectMap::<T>>() else { unreachable!() };
+        actual_map.put(id, Arc::clone(&value));
+        Ok(value)
     }
 }
 impl<T: PZObject + 'static> Serialize for Ptr<T> {
@@ -171,6 +281,43 @@ pub static CACHE_CLIENT: Lazy<ClientWithMiddleware> = Lazy::new(|| {
         .build()
 });
 
+/// Single-flight registry for [`PZFile::fetch`], keyed by URL since a `PZFile` has no numeric id.
+static FILE_WRITING_STATUS: Lazy<Mutex<HashMap<String, Weak<SharedFetch<Bytes>>>>> = Lazy::new(Mutex::default);
+
+/// Joins an outstanding call for `key` in `registry`, or starts one via `make` and registers it —
+/// the same atomic check-or-insert, identity-safe-cleanup pattern as
+/// [`get_or_start_inflight`]/[`clear_inflight`], just against a concrete (non-type-erased)
+/// registry. `make` only constructs the future (it must not be polled yet), so it's safe to call
+/// while `registry`'s lock is held.
+async fn coalesce<K, V>(registry: &Mutex<HashMap<K, Weak<SharedFetch<V>>>>, key: K, make: impl FnOnce() -> BoxFuture<'static, FetchOutcome<V>>) -> FetchOutcome<V>
+where
+    K: std::hash::Hash + Eq + Clone,
+    V: Clone,
+{
+    let shared = {
+        let mut status = registry.lock().unwrap();
+        if let Some(shared) = status.get(&key).and_then(Weak::upgrade) {
+            shared
+        } else {
+            let shared: Arc<SharedFetch<V>> = Arc::new(make().shared());
+            status.insert(key.clone(), Arc::downgrade(&shared));
+            shared
+        }
+    };
+    let result = (*shared).clone().await;
+    // remove on both success and error so a failed fetch isn't permanently memoized, but only if
+    // the entry still points at our own handle — a late waiter must not clobber a newer
+    // generation's still-in-flight registration for the same key
+    {
+        let mut status = registry.lock().unwrap();
+        let still_current = status.get(&key).and_then(Weak::upgrade).map_or(false, |current| Arc::ptr_eq(&current, &shared));
+        if still_current {
+            status.remove(&key);
+        }
+    }
+    result
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct PZFile {
@@ -178,18 +325,57 @@ pub struct PZFile {
 }
 impl PZFile {
     pub async fn fetch(&self) -> Result<Bytes> {
-        Ok(CACHE_CLIENT.get(&self.url).send().await?.bytes().await?)
+        if let Some(cached) = FILE_CACHE.get(&self.url).await {
+            return Ok(cached);
+        }
+
+        let url = self.url.clone();
+        coalesce(&FILE_WRITING_STATUS, self.url.clone(), move || {
+            async move { Self::fetch_and_cache(&url).await.map_err(Arc::new) }.boxed()
+        })
+        .await
+        .map_err(|err| anyhow::anyhow!(err))
+    }
+
+    async fn fetch_and_cache(url: &str) -> Result<Bytes> {
+        let bytes = CACHE_CLIENT.get(url).send().await?.bytes().await?;
+        let _ = FILE_CACHE.put(url, &bytes).await;
+        Ok(bytes)
     }
 
     pub async fn fetch_stream(&self) -> Result<impl Stream<Item = reqwest::Result<Bytes>>> {
-        Ok(CACHE_CLIENT.get(&self.url).send().await?.bytes_stream())
+        if let Some(cached) = FILE_CACHE.get(&self.url).await {
+            return Ok(futures_util::stream::once(async { Ok(cached) }).boxed());
+        }
+
+        let upstream = CACHE_CLIENT.get(&self.url).send().await?.bytes_stream();
+        // caching is best-effort: if we can't open a writer, still stream the response through
+        match Arc::clone(&FILE_CACHE).writer(&self.url).await {
+            Ok(writer) => Ok(tee_to_cache(writer, upstream).boxed()),
+            Err(_) => Ok(upstream.boxed()),
+        }
     }
 
     pub async fn load_image(&self) -> Result<DynamicImage> {
         Ok(image::load_from_memory(&self.fetch().await?)?)
     }
 
+    /// Renders a tiny BlurHash placeholder string for this image, so a UI can show a gradient
+    /// while the full illustration/thumbnail loads. `components_x`/`components_y` must each be
+    /// in `1..=9`.
+    pub async fn load_blurhash(&self, components_x: u32, components_y: u32) -> Result<String> {
+        blurhash::encode(&self.load_thumbnail().await?, components_x, components_y)
+    }
+
     pub async fn load_thumbnail(&self) -> Result<DynamicImage> {
+        self.load_thumbnail_with(false).await
+    }
+
+    /// Like [`load_thumbnail`](Self::load_thumbnail), but `force_regenerate` bypasses a
+    /// previously cached local resize (e.g. for tests). The cache key already includes
+    /// `THUMBNAIL_WIDTH`/`HEIGHT`, so changing those never hits an older entry.
+    pub async fn load_thumbnail_with(&self, force_regenerate: bool) -> Result<DynamicImage> {
+        // known image CDNs can downsize for us via a query string, so skip decoding entirely
         if self.url.starts_with("https://phira.mivik.cn/") {
             return PZFile {
                 url: format!("{}?imageView/0/w/{THUMBNAIL_WIDTH}/h/{THUMBNAIL_HEIGHT}", self.url),
@@ -197,6 +383,122 @@ impl PZFile {
             .load_image()
             .await;
         }
-        self.load_image().await
+
+        let cache_key = format!("{}#thumb-{THUMBNAIL_WIDTH}x{THUMBNAIL_HEIGHT}", self.url);
+        if !force_regenerate {
+            if let Some(cached) = FILE_CACHE.get(&cache_key).await {
+                return Ok(image::load_from_memory(&cached)?);
+            }
+        }
+
+        let thumbnail = self
+            .load_image()
+            .await?
+            .resize(THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT, image::imageops::FilterType::Lanczos3);
+
+        let mut encoded = Vec::new();
+        if thumbnail
+            .write_to(&mut std::io::Cursor::new(&mut encoded), image::ImageFormat::Png)
+            .is_ok()
+        {
+            let _ = FILE_CACHE.put(&cache_key, &encoded).await;
+        }
+        Ok(thumbnail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+    #[derive(Clone, Serialize, Deserialize)]
+    struct FakeObject {
+        id: i32,
+    }
+    impl PZObject for FakeObject {
+        const QUERY_PATH: &'static str = "__test_fake_object__";
+
+        fn id(&self) -> i32 {
+            self.id
+        }
+    }
+
+    static NEXT_TEST_ID: AtomicI32 = AtomicI32::new(1);
+
+    /// A fresh id per call, so concurrently-run tests sharing `FakeObject::QUERY_PATH` never
+    /// collide on the same `WRITING_STATUS` key.
+    fn next_test_id() -> i32 {
+        NEXT_TEST_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
+    #[tokio::test]
+    async fn get_or_start_inflight_coalesces_concurrent_callers() {
+        let id = next_test_id();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut handles = Vec::new();
+        for _ in 0..16 {
+            let calls = Arc::clone(&calls);
+            handles.push(tokio::spawn(async move {
+                let shared = get_or_start_inflight::<FakeObject>(id, {
+                    let calls = Arc::clone(&calls);
+                    move || {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        async move {
+                            tokio::task::yield_now().await;
+                            Ok(Arc::new(FakeObject { id }))
+                        }
+                        .boxed()
+                    }
+                });
+                let result = (*shared).clone().await;
+                clear_inflight::<FakeObject>(id, &shared);
+                result
+            }));
+        }
+
+        for handle in handles {
+            assert!(handle.await.unwrap().is_ok());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1, "all concurrent callers must join the same in-flight fetch");
+
+        // the registry must be clean afterwards: a later call starts a fresh fetch rather than
+        // reusing (or failing to find) a stale entry
+        let shared = get_or_start_inflight::<FakeObject>(id, {
+            let calls = Arc::clone(&calls);
+            move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async move { Ok(Arc::new(FakeObject { id })) }.boxed()
+            }
+        });
+        (*shared).clone().await.unwrap();
+        clear_inflight::<FakeObject>(id, &shared);
+        assert_eq!(calls.load(Ordering::SeqCst), 2, "a call after the first one finished must not join a stale entry");
+    }
+
+    #[tokio::test]
+    async fn coalesce_joins_concurrent_callers_for_the_same_key() {
+        let registry: Mutex<HashMap<String, Weak<SharedFetch<Bytes>>>> = Mutex::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let results = futures_util::future::join_all((0..16).map(|_| {
+            let calls = Arc::clone(&calls);
+            coalesce(&registry, "some-url".to_owned(), {
+                let calls = Arc::clone(&calls);
+                move || {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        tokio::task::yield_now().await;
+                        Ok(Bytes::from_static(b"payload"))
+                    }
+                    .boxed()
+                }
+            })
+        }))
+        .await;
+
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(calls.load(Ordering::SeqCst), 1, "all concurrent callers must join the same in-flight fetch");
     }
 }
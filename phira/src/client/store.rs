@@ -0,0 +1,257 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+/// Where cached `PZFile` bytes and serialized `Ptr<T>` objects live. [`FsStore`] is the default,
+/// local-disk backend; [`S3Store`] lets a shared cache be hosted in object storage so multiple
+/// instances can share one cache.
+#[async_trait]
+pub trait Store: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<Bytes>>;
+    async fn put(&self, key: &str, data: Bytes) -> Result<()>;
+    async fn remove(&self, key: &str) -> Result<()>;
+    async fn exists(&self, key: &str) -> Result<bool>;
+}
+
+/// The current on-disk behavior, lifted behind [`Store`]: writes go to a temp file that is
+/// atomically renamed into place.
+pub struct FsStore {
+    dir: PathBuf,
+}
+impl FsStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let _ = std::fs::create_dir_all(&dir);
+        Self { dir }
+    }
+
+    fn path_for(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+}
+#[async_trait]
+impl Store for FsStore {
+    async fn get(&self, key: &str) -> Result<Option<Bytes>> {
+        match tokio::fs::read(self.path_for(key)).await {
+            Ok(bytes) => Ok(Some(Bytes::from(bytes))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn put(&self, key: &str, data: Bytes) -> Result<()> {
+        let tmp_path = self.dir.join(format!("{key}.tmp"));
+        tokio::fs::write(&tmp_path, &data)
+            .await
+            .with_context(|| format!("writing temp file for {key}"))?;
+        tokio::fs::rename(&tmp_path, self.path_for(key))
+            .await
+            .with_context(|| format!("renaming cache entry for {key}"))?;
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path_for(key)).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        Ok(tokio::fs::metadata(self.path_for(key)).await.is_ok())
+    }
+}
+
+/// Configuration for an S3-compatible bucket: works against real AWS S3 as well as compatible
+/// services (MinIO, R2, B2, ...) that accept the same SigV4-signed REST API.
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+/// `Store` backed by an S3-compatible bucket, signing each request with AWS SigV4 over
+/// `reqwest` so a shared cache can be hosted in object storage for multi-instance deployments.
+pub struct S3Store {
+    config: S3Config,
+    client: reqwest::Client,
+}
+impl S3Store {
+    pub fn new(config: S3Config) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        format!("{}/{}/{}", self.config.endpoint.trim_end_matches('/'), self.config.bucket, key)
+    }
+
+    async fn signed_request(&self, method: reqwest::Method, key: &str, body: Bytes) -> Result<reqwest::RequestBuilder> {
+        let url = self.object_url(key);
+        let parsed = reqwest::Url::parse(&url).with_context(|| format!("invalid S3 url for key {key}"))?;
+        let host = parsed.host_str().context("S3 endpoint has no host")?.to_owned();
+        let path = parsed.path().to_owned();
+        let now = sigv4::now();
+        let payload_hash = sigv4::sha256_hex(&body);
+
+        let headers = sigv4::sign(
+            &sigv4::SigningParams {
+                method: method.as_str(),
+                path: &path,
+                host: &host,
+                region: &self.config.region,
+                service: "s3",
+                access_key: &self.config.access_key,
+                secret_key: &self.config.secret_key,
+                payload_hash: &payload_hash,
+                timestamp: now,
+            },
+        );
+
+        let mut request = self.client.request(method, url).body(body);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        Ok(request)
+    }
+}
+#[async_trait]
+impl Store for S3Store {
+    async fn get(&self, key: &str) -> Result<Option<Bytes>> {
+        let response = self.signed_request(reqwest::Method::GET, key, Bytes::new()).await?.send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.bytes().await?))
+    }
+
+    async fn put(&self, key: &str, data: Bytes) -> Result<()> {
+        self.signed_request(reqwest::Method::PUT, key, data)
+            .await?
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        self.signed_request(reqwest::Method::DELETE, key, Bytes::new())
+            .await?
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        let response = self.signed_request(reqwest::Method::HEAD, key, Bytes::new()).await?.send().await?;
+        Ok(response.status().is_success())
+    }
+}
+
+/// Minimal AWS SigV4 request signer, just enough to authenticate the GET/PUT/DELETE/HEAD
+/// object calls [`S3Store`] needs.
+mod sigv4 {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    pub struct SigningParams<'a> {
+        pub method: &'a str,
+        pub path: &'a str,
+        pub host: &'a str,
+        pub region: &'a str,
+        pub service: &'a str,
+        pub access_key: &'a str,
+        pub secret_key: &'a str,
+        pub payload_hash: &'a str,
+        pub timestamp: (String, String),
+    }
+
+    /// Returns `(amz_date, date_stamp)`, e.g. `("20240101T000000Z", "20240101")`.
+    pub fn now() -> (String, String) {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let (year, month, day) = civil_from_unix_days((secs / 86400) as i64);
+        let time_of_day = secs % 86400;
+        let amz_date = format!(
+            "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+            time_of_day / 3600,
+            (time_of_day % 3600) / 60,
+            time_of_day % 60
+        );
+        let date_stamp = amz_date[..8].to_owned();
+        (amz_date, date_stamp)
+    }
+
+    /// Howard Hinnant's `civil_from_days`, converting a day count since the Unix epoch to a
+    /// proleptic-Gregorian `(year, month, day)` triple without pulling in a date/time crate.
+    fn civil_from_unix_days(days: i64) -> (i64, u32, u32) {
+        let z = days + 719468;
+        let era = if z >= 0 { z } else { z - 146096 } / 146097;
+        let doe = (z - era * 146097) as u64;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let y = yoe as i64 + era * 400;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = if month <= 2 { y + 1 } else { y };
+        (year, month, day)
+    }
+
+    pub fn sha256_hex(data: &[u8]) -> String {
+        hex_encode(Sha256::digest(data).as_slice())
+    }
+
+    fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn hex_encode(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    pub fn sign(params: &SigningParams) -> Vec<(String, String)> {
+        let (amz_date, date_stamp) = &params.timestamp;
+
+        let canonical_headers = format!("host:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n", params.host, params.payload_hash, amz_date);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            params.method, params.path, canonical_headers, signed_headers, params.payload_hash
+        );
+
+        let credential_scope = format!("{date_stamp}/{}/{}/aws4_request", params.region, params.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{credential_scope}\n{}",
+            hex_encode(Sha256::digest(canonical_request.as_bytes()).as_slice())
+        );
+
+        let k_date = hmac_sha256(format!("AWS4{}", params.secret_key).as_bytes(), date_stamp.as_bytes());
+        let k_region = hmac_sha256(&k_date, params.region.as_bytes());
+        let k_service = hmac_sha256(&k_region, params.service.as_bytes());
+        let k_signing = hmac_sha256(&k_service, b"aws4_request");
+        let signature = hex_encode(&hmac_sha256(&k_signing, string_to_sign.as_bytes()));
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{credential_scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            params.access_key
+        );
+
+        vec![
+            ("host".to_owned(), params.host.to_owned()),
+            ("x-amz-content-sha256".to_owned(), params.payload_hash.to_owned()),
+            ("x-amz-date".to_owned(), amz_date.clone()),
+            ("authorization".to_owned(), authorization),
+        ]
+    }
+}